@@ -1,17 +1,120 @@
 //! Exports [`DuoApi`] for all API related functionality.
 
-use fake::{
-    faker::internet::en::{FreeEmail, Password, UserAgent},
-    Fake,
-};
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use jsonwebtoken::{decode, decode_header, DecodingKey, Validation};
 use reqwest::{
-    blocking::{Client, ClientBuilder, Response},
-    header::COOKIE,
-    redirect::Policy,
+    header::{COOKIE, USER_AGENT},
+    redirect::Policy, Client, ClientBuilder, Response, StatusCode,
 };
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::generator;
+
+/// Maximum number of attempts made for a single request before giving up,
+/// counting the initial attempt.
+const MAX_ATTEMPTS: u32 = 4;
+
+/// Everything that can go wrong while talking to the Duo API, each mapped to a
+/// distinct process exit code (see [`DuoApiError::exit_code`]) so a caller can
+/// tell transient network failures apart from a rejected request or malformed
+/// response without parsing strings.
+#[derive(Debug)]
+pub enum DuoApiError {
+    /// The request itself failed (DNS, TLS, timeout, connection reset, ...).
+    Http(reqwest::Error),
+    /// The account creation response didn't include the `jwt` header we rely
+    /// on for subsequent requests.
+    MissingJwt,
+    /// Duo responded with a non-2xx status.
+    BadStatus(StatusCode),
+    /// The response body couldn't be parsed into the expected shape.
+    Parse,
+    /// The `jwt` header was malformed, expired, or didn't claim the user id we
+    /// just created.
+    InvalidJwt(String),
+    /// [`DuoApi::health_check`] determined the referral code doesn't resolve
+    /// to a valid inviter.
+    HealthCheckFailed(String),
+}
+
+impl fmt::Display for DuoApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Http(err) => write!(f, "request to Duo failed: {err}"),
+            Self::MissingJwt => write!(f, "response did not contain the expected `jwt` header"),
+            Self::BadStatus(status) => write!(f, "Duo responded with status {status}"),
+            Self::Parse => write!(f, "failed to parse Duo's response"),
+            Self::InvalidJwt(reason) => write!(f, "invalid JWT: {reason}"),
+            Self::HealthCheckFailed(detail) => write!(f, "health check failed: {detail}"),
+        }
+    }
+}
+
+impl std::error::Error for DuoApiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Http(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for DuoApiError {
+    fn from(err: reqwest::Error) -> Self {
+        Self::Http(err)
+    }
+}
+
+impl DuoApiError {
+    /// The process exit code this error should be surfaced as, grouped by
+    /// category so scripts driving this tool can distinguish failure modes.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Http(_) => 1,
+            Self::MissingJwt => 2,
+            Self::BadStatus(_) => 3,
+            Self::Parse => 4,
+            Self::InvalidJwt(_) => 5,
+            Self::HealthCheckFailed(_) => 6,
+        }
+    }
+
+    /// Whether this error is likely transient (a network hiccup or a 5xx)
+    /// and worth retrying, as opposed to one that will fail identically on
+    /// every attempt.
+    fn is_transient(&self) -> bool {
+        match self {
+            Self::Http(_) => true,
+            Self::BadStatus(status) => status.is_server_error(),
+            _ => false,
+        }
+    }
+}
+
+/// Retries `f` with exponential backoff while it keeps failing with a
+/// transient [`DuoApiError`], up to [`MAX_ATTEMPTS`] total attempts.
+async fn with_retry<F, Fut, T>(mut f: F) -> Result<T, DuoApiError>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, DuoApiError>>,
+{
+    let mut attempt = 1;
+
+    loop {
+        match f().await {
+            Ok(value) => return Ok(value),
+            Err(err) if attempt < MAX_ATTEMPTS && err.is_transient() => {
+                tokio::time::sleep(Duration::from_millis(200 * 2u64.pow(attempt))).await;
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
 /// The API used to create and patch users. This specific API version is the
 /// only one that supports this strategy.
 pub const BASE_USERS_URL: &str = "https://www.duolingo.com/2017-06-30/users";
@@ -54,8 +157,8 @@ pub struct UserCreationData {
 /// dummy values.
 #[derive(Serialize, Default)]
 pub struct UserCredentialsData {
-    email: String,
-    password: String,
+    pub(crate) email: String,
+    pub(crate) password: String,
     age: String,
     signal: UserCreationSignal,
 }
@@ -68,30 +171,87 @@ pub struct UserCreationResponse {
     id: u32,
 }
 
+/// The response returned when probing whether a referral code resolves to a
+/// valid inviter via [`DuoApi::health_check`].
+#[derive(Deserialize, Default)]
+struct HealthCheckResponse {
+    #[serde(default)]
+    detail: Option<String>,
+}
+
 /// All relevant data from creating the user needed to create credentials. This
 /// includes the user ID and the JWT token returned from the API when creating
 /// the user.
 pub struct AccountData {
-    id: u32,
-    token: String,
+    pub(crate) id: u32,
+    pub(crate) token: String,
 }
 
-impl From<Response> for AccountData {
+/// The claims we care about in the `jwt` header Duo returns: who it's for,
+/// and when it stops being usable. Duo's exact claim set isn't guaranteed, so
+/// both are optional — a missing claim just means we skip that check rather
+/// than treat the token as invalid.
+#[derive(Deserialize)]
+struct DuoClaims {
+    #[serde(default)]
+    sub: Option<String>,
+    #[serde(default)]
+    exp: Option<usize>,
+}
+
+/// Decodes `token` and, best-effort, confirms it claims the just-created `id`
+/// and isn't already expired. We don't hold Duo's signing key, so signature
+/// verification is disabled. A missing `exp`/`sub` claim is not fatal; only a
+/// token that can't be decoded at all, or one that explicitly claims the
+/// wrong subject or an already-past expiry, is.
+fn validate_jwt(token: &str, id: u32) -> Result<(), DuoApiError> {
+    let header = decode_header(token).map_err(|_| DuoApiError::InvalidJwt("malformed header".to_string()))?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.insecure_disable_signature_validation();
+    validation.validate_exp = false;
+    validation.validate_aud = false;
+    validation.required_spec_claims.clear();
+
+    let claims = decode::<DuoClaims>(token, &DecodingKey::from_secret(&[]), &validation)
+        .map_err(|err| DuoApiError::InvalidJwt(err.to_string()))?
+        .claims;
+
+    if let Some(exp) = claims.exp {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs() as usize);
+
+        if exp < now {
+            return Err(DuoApiError::InvalidJwt(format!("token already expired at {exp}")));
+        }
+    }
+
+    if let Some(sub) = claims.sub {
+        if sub != id.to_string() {
+            return Err(DuoApiError::InvalidJwt(format!("subject {sub} does not match created user id {id}")));
+        }
+    }
+
+    Ok(())
+}
+
+impl AccountData {
     /// Finds the JWT token from the request to be reused on the next request as
-    /// a form of authentication, and then deserializes the response as JSON to
-    /// retrieve the user id.
-    fn from(res: Response) -> Self {
-        let token = res.headers()["jwt"]
+    /// a form of authentication, validates it (see [`validate_jwt`]), and then
+    /// deserializes the response as JSON to retrieve the user id.
+    async fn try_from_response(res: Response) -> Result<Self, DuoApiError> {
+        let token = res
+            .headers()
+            .get("jwt")
+            .ok_or(DuoApiError::MissingJwt)?
             .to_str()
-            .expect("JWT token was not found in the account creation response headers")
+            .map_err(|_| DuoApiError::MissingJwt)?
             .to_string();
 
-        let id = res
-            .json::<UserCreationResponse>()
-            .expect("Failed to parse user creation response")
-            .id;
+        let id = res.json::<UserCreationResponse>().await.map_err(|_| DuoApiError::Parse)?.id;
+
+        validate_jwt(&token, id)?;
 
-        Self { id, token }
+        Ok(Self { id, token })
     }
 }
 
@@ -102,12 +262,12 @@ pub struct DuoApi {
 }
 
 impl Default for DuoApi {
-    /// Creates a new API client with a reusable User-Agent.
+    /// Creates a new API client. The User-Agent is deliberately not set here:
+    /// it's picked fresh per request (see [`generator::random_user_agent`])
+    /// so a batch run isn't sent entirely under one static signature.
     fn default() -> Self {
         Self {
             client: ClientBuilder::new()
-                // The user agent will make the request look less like a bot's.
-                .user_agent(UserAgent().fake::<&str>())
                 // [`DuoApi::get_user_id`] makes a request that will try to redirect the user, which we don't want.
                 .redirect(Policy::none())
                 .build()
@@ -130,46 +290,101 @@ impl DuoApi {
         }
     }
 
-    /// Creates a new user via the provided referral code (see
-    /// [`UserCreationData`]), and constructs a [`AccountData`] from it.
-    pub fn create_account(&self, code: &str) -> AccountData {
-        let creation_data = UserCreationData {
-            timezone: String::from("America/Montreal"),
-            from_language: String::from("en"),
-            invite_code: code.to_string(),
-            distinct_id: Uuid::new_v4().to_string(),
-            ..Default::default()
-        };
-
+    /// Probes that `code` resolves to a valid inviter before any accounts are
+    /// created, so a typo'd referral code or a missing VPN is caught up
+    /// front instead of after a batch of confusing failures.
+    pub async fn health_check(&self, code: &str) -> Result<(), DuoApiError> {
         let res = self
             .client
-            .post(format!("{BASE_USERS_URL}?fields=id"))
-            .json(&creation_data)
+            .get(format!("{BASE_USERS_URL}?fields=id&inviteCode={code}"))
+            .header(USER_AGENT, generator::random_user_agent())
             .send()
-            .unwrap()
-            .error_for_status()
-            .expect("Failed to create account");
-
-        res.into()
-    }
-
-    /// Creates credentials for the user (see [`UserCredentialsData`]) from [`AccountData`].
-    pub fn create_credentials(&self, data: &AccountData) {
-        let user_data = UserCredentialsData {
-            age: String::from("5"),
-            email: FreeEmail().fake(),
-            password: Password(15..16).fake(),
-            ..Default::default()
-        };
-
-        self.client
-            .patch(format!("{BASE_USERS_URL}/{}?fields=email,identifier,name,username", data.id))
-            .header(COOKIE, format!("jwt_token={}", data.token))
-            .json(&user_data)
-            .send()
-            .unwrap()
-            .error_for_status()
-            .expect("Failed to create credentials");
+            .await?;
+
+        if res.status().is_success() {
+            return Ok(());
+        }
+
+        let detail = res
+            .json::<HealthCheckResponse>()
+            .await
+            .ok()
+            .and_then(|body| body.detail)
+            .unwrap_or_else(|| String::from("referral code did not resolve to a valid inviter"));
+
+        Err(DuoApiError::HealthCheckFailed(detail))
+    }
+
+    /// Creates a new user via the provided referral code (see
+    /// [`UserCreationData`]), and constructs a [`AccountData`] from it. Retries
+    /// transient failures with backoff (see [`with_retry`]).
+    ///
+    /// This POST is not idempotent, and the retry wraps the whole thing,
+    /// including reading and parsing the response: if Duo creates the user
+    /// but the attempt still fails as transient (a 5xx after the fact, or a
+    /// timeout reading the body), the retry will create a second account
+    /// under the same code. There's no idempotency key or dedup endpoint
+    /// this API exposes to retry around, so this is a known, accepted risk
+    /// rather than a bug to silently swallow — a run can consume more
+    /// referral slots than `--num` requested and overcount `num_created` as
+    /// a result.
+    pub async fn create_account(&self, code: &str, timezone: &str, from_language: &str) -> Result<AccountData, DuoApiError> {
+        with_retry(|| async {
+            let creation_data = UserCreationData {
+                timezone: timezone.to_string(),
+                from_language: from_language.to_string(),
+                invite_code: code.to_string(),
+                distinct_id: Uuid::new_v4().to_string(),
+                ..Default::default()
+            };
+
+            let res = self
+                .client
+                .post(format!("{BASE_USERS_URL}?fields=id"))
+                .header(USER_AGENT, generator::random_user_agent())
+                .json(&creation_data)
+                .send()
+                .await?
+                .error_for_status()
+                .map_err(|err| DuoApiError::BadStatus(err.status().unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)))?;
+
+            AccountData::try_from_response(res).await
+        })
+        .await
+    }
+
+    /// Creates credentials for the user (see [`UserCredentialsData`]) from
+    /// [`AccountData`], returning the generated credentials so the caller can
+    /// persist them for later use. `password_len` is clamped to
+    /// [`generator::MIN_PASSWORD_LEN`]..=[`generator::MAX_PASSWORD_LEN`]. Retries
+    /// transient failures with backoff (see [`with_retry`]).
+    pub async fn create_credentials(
+        &self,
+        data: &AccountData,
+        password_len: usize,
+        age: &str,
+    ) -> Result<UserCredentialsData, DuoApiError> {
+        with_retry(|| async {
+            let user_data = UserCredentialsData {
+                age: age.to_string(),
+                email: generator::generate_email(),
+                password: generator::generate_password(password_len),
+                ..Default::default()
+            };
+
+            self.client
+                .patch(format!("{BASE_USERS_URL}/{}?fields=email,identifier,name,username", data.id))
+                .header(COOKIE, format!("jwt_token={}", data.token))
+                .header(USER_AGENT, generator::random_user_agent())
+                .json(&user_data)
+                .send()
+                .await?
+                .error_for_status()
+                .map_err(|err| DuoApiError::BadStatus(err.status().unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)))?;
+
+            Ok(user_data)
+        })
+        .await
     }
 }
 
@@ -0,0 +1,87 @@
+//! Cryptographically secure generation of account credentials and
+//! User-Agent strings, replacing the `fake` crate's small, predictable
+//! corpora, which both weaken generated passwords and make the traffic more
+//! fingerprintable as automation.
+
+use rand::Rng;
+
+/// Digits + upper + lower-case ascii characters drawn from to build emails
+/// and passwords.
+pub const CHAR_POOL: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+/// The minimum length enforced for generated passwords. Kept at least as
+/// long as the baseline `fake` crate's `Password(15..16)` so replacing it
+/// can't silently produce weaker passwords.
+pub const MIN_PASSWORD_LEN: usize = 16;
+/// The maximum length enforced for generated passwords.
+pub const MAX_PASSWORD_LEN: usize = 24;
+
+/// The length used for the local part of generated emails.
+const EMAIL_LOCAL_PART_LEN: usize = 12;
+/// The domain generated emails are created under.
+const EMAIL_DOMAIN: &str = "gmail.com";
+
+/// A rotating pool of modern, real-world User-Agent strings to draw from, so
+/// requests aren't all sent under a single static signature.
+const USER_AGENTS: &[&str] = &[
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.4 Safari/605.1.15",
+    "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:125.0) Gecko/20100101 Firefox/125.0",
+    "Mozilla/5.0 (iPhone; CPU iPhone OS 17_4 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.4 Mobile/15E148 Safari/604.1",
+];
+
+/// Draws a random string of `len` characters from `pool` using a CSPRNG.
+pub fn get_random_string(pool: &[u8], len: usize) -> String {
+    let mut rng = rand::thread_rng();
+    (0..len).map(|_| pool[rng.gen_range(0..pool.len())] as char).collect()
+}
+
+/// Generates a random, plausible-looking email address.
+pub fn generate_email() -> String {
+    format!("{}@{EMAIL_DOMAIN}", get_random_string(CHAR_POOL, EMAIL_LOCAL_PART_LEN).to_lowercase())
+}
+
+/// Generates a random password, clamped to
+/// `[MIN_PASSWORD_LEN, MAX_PASSWORD_LEN]`.
+pub fn generate_password(len: usize) -> String {
+    get_random_string(CHAR_POOL, len.clamp(MIN_PASSWORD_LEN, MAX_PASSWORD_LEN))
+}
+
+/// Picks a random User-Agent string from [`USER_AGENTS`].
+pub fn random_user_agent() -> &'static str {
+    let mut rng = rand::thread_rng();
+    USER_AGENTS[rng.gen_range(0..USER_AGENTS.len())]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_string_has_requested_length_and_pool() {
+        let string = get_random_string(CHAR_POOL, 100);
+        assert_eq!(string.len(), 100);
+        assert!(string.bytes().all(|byte| CHAR_POOL.contains(&byte)));
+    }
+
+    #[test]
+    fn password_respects_min_and_max_length() {
+        assert_eq!(generate_password(0).len(), MIN_PASSWORD_LEN);
+        assert_eq!(generate_password(MIN_PASSWORD_LEN).len(), MIN_PASSWORD_LEN);
+        assert_eq!(generate_password(MAX_PASSWORD_LEN).len(), MAX_PASSWORD_LEN);
+        assert_eq!(generate_password(usize::MAX).len(), MAX_PASSWORD_LEN);
+    }
+
+    #[test]
+    fn email_has_expected_shape() {
+        let email = generate_email();
+        assert!(email.ends_with(&format!("@{EMAIL_DOMAIN}")));
+        assert_eq!(email.len(), EMAIL_LOCAL_PART_LEN + 1 + EMAIL_DOMAIN.len());
+    }
+
+    #[test]
+    fn user_agent_comes_from_the_pool() {
+        assert!(USER_AGENTS.contains(&random_user_agent()));
+    }
+}
@@ -18,12 +18,20 @@
 //! free-duolingo-plus --code https://invite.duolingo.com/BDHTZTB5CWWKTVW2UCDTY27MBE --num 10
 //! ```
 
+use std::path::PathBuf;
+use std::process;
+
 use clap::{value_parser, AppSettings, Parser};
 use console::style;
-use indicatif::{ProgressBar, ProgressIterator, ProgressStyle};
+use futures::stream::{self, StreamExt};
+use indicatif::{ProgressBar, ProgressStyle};
 
 pub mod duo_api;
-use crate::duo_api::DuoApi;
+pub mod generator;
+pub mod output;
+use crate::duo_api::{DuoApi, DuoApiError};
+use crate::generator::{MAX_PASSWORD_LEN, MIN_PASSWORD_LEN};
+use crate::output::{AccountRecord, OutputFormat, OutputWriter};
 
 /// Struct used to resolve CLI arguments.
 #[derive(Parser, Debug)]
@@ -45,26 +53,121 @@ struct Args {
         value_parser = value_parser!(u8).range(1..=24)
     )]
     num: u8,
+
+    #[clap(
+        short,
+        long,
+        help = "Path to write generated account credentials to",
+        default_value = "accounts.jsonl"
+    )]
+    output: PathBuf,
+
+    #[clap(short, long, help = "The format to write account credentials in", value_enum, default_value_t = OutputFormat::Jsonl)]
+    format: OutputFormat,
+
+    #[clap(
+        long,
+        help = "The length of generated passwords",
+        default_value_t = MAX_PASSWORD_LEN,
+        value_parser = value_parser!(usize).range(MIN_PASSWORD_LEN as i64..=MAX_PASSWORD_LEN as i64)
+    )]
+    password_length: usize,
+
+    #[clap(long, help = "The number of accounts to create concurrently", default_value_t = 4, value_parser = value_parser!(u8).range(1..=24))]
+    concurrency: u8,
+
+    #[clap(long, env = "DUO_TIMEZONE", help = "Timezone reported when creating accounts", default_value = "America/Montreal")]
+    timezone: String,
+
+    #[clap(long, env = "DUO_FROM_LANGUAGE", help = "Source language reported when creating accounts", default_value = "en")]
+    from_language: String,
+
+    #[clap(long, env = "DUO_AGE", help = "Age reported when creating account credentials", default_value = "5")]
+    age: String,
+}
+
+/// Creates a single account end-to-end, returning the record worth
+/// persisting.
+async fn create_one(client: &DuoApi, args: &Args) -> Result<AccountRecord, DuoApiError> {
+    let data = client.create_account(&args.code, &args.timezone, &args.from_language).await?;
+    let creds = client.create_credentials(&data, args.password_length, &args.age).await?;
+    Ok(AccountRecord { email: creds.email, password: creds.password, id: data.id, jwt: data.token })
 }
 
 /// CLI entrypoint.
-fn main() {
+#[tokio::main]
+async fn main() {
     let args = Args::parse();
     let client = DuoApi::default();
 
+    if let Err(err) = client.health_check(&args.code).await {
+        eprintln!("{} {err}", style("Error:").red().bold());
+        process::exit(err.exit_code());
+    }
+
+    let mut output = OutputWriter::new(&args.output, args.format).unwrap_or_else(|err| {
+        eprintln!("{} failed to open {}: {err}", style("Error:").red().bold(), args.output.display());
+        process::exit(1);
+    });
+
     let bar_style = ProgressStyle::default_bar() //
         .template("[{elapsed_precise}] [{pos}/{len}] {bar:70.cyan}");
 
     let bar = ProgressBar::new(args.num.into()).with_style(bar_style);
 
-    for _ in ProgressIterator::progress_with(1..=args.num, bar) {
-        let data = client.create_account(&args.code);
-        client.create_credentials(&data);
+    let mut created = stream::iter(1..=args.num)
+        .map(|_| {
+            let bar = bar.clone();
+            async {
+                let result = create_one(&client, &args).await;
+                bar.inc(1);
+                result
+            }
+        })
+        .buffer_unordered(args.concurrency.into());
+
+    let mut num_created = 0u8;
+    // All exit codes seen across the run, so the code reported on total
+    // failure is picked by a fixed rule (lowest code wins) rather than by
+    // whichever failure happened to finish last under `buffer_unordered`,
+    // which would be nondeterministic across otherwise-identical runs.
+    let mut exit_codes = std::collections::BTreeSet::new();
+
+    while let Some(result) = created.next().await {
+        match result {
+            Ok(record) => {
+                if let Err(err) = output.write(&record) {
+                    eprintln!("{} failed to write to {}: {err}", style("Error:").red().bold(), args.output.display());
+                    process::exit(1);
+                }
+
+                num_created += 1;
+            }
+            Err(err) => {
+                eprintln!("{} {err}", style("Warning:").yellow().bold());
+                exit_codes.insert(err.exit_code());
+            }
+        }
+    }
+
+    bar.finish();
+
+    if num_created == 0 {
+        eprintln!("{} every account failed to create", style("Error:").red().bold());
+        process::exit(exit_codes.into_iter().next().unwrap_or(1));
     }
 
     println!(
-        "All accounts created! Enjoy your {} weeks of free Duolingo Plus.\n{}",
-        style(args.num).green().bold(),
+        "{} accounts created! Enjoy your {} weeks of free Duolingo Plus.\n{}",
+        style(num_created).green().bold(),
+        style(num_created).green().bold(),
         style("https://www.duolingo.com/").dim()
     );
+
+    // Some accounts having succeeded doesn't mean the run as a whole did:
+    // surface the same deterministic per-category code for a partial
+    // failure as we would for a total one, rather than exiting 0.
+    if let Some(code) = exit_codes.into_iter().next() {
+        process::exit(code);
+    }
 }
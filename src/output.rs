@@ -0,0 +1,133 @@
+//! Exports [`OutputWriter`] for persisting generated account credentials to
+//! disk, so the accounts this tool creates can actually be logged into again
+//! afterwards.
+
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use serde::Serialize;
+
+/// The on-disk format used to persist generated account credentials.
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum OutputFormat {
+    /// One JSON object per line.
+    Jsonl,
+    /// Comma-separated values, with a header row.
+    Csv,
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Jsonl => write!(f, "jsonl"),
+            Self::Csv => write!(f, "csv"),
+        }
+    }
+}
+
+/// A single account's worth of data worth persisting: enough to log back
+/// into the account later.
+#[derive(Serialize)]
+pub struct AccountRecord {
+    pub email: String,
+    pub password: String,
+    pub id: u32,
+    pub jwt: String,
+}
+
+/// Writes [`AccountRecord`]s to disk in the configured [`OutputFormat`],
+/// flushing after every record so a crash mid-batch still preserves what was
+/// made.
+pub enum OutputWriter {
+    Jsonl(File),
+    Csv(csv::Writer<File>),
+}
+
+impl OutputWriter {
+    /// Opens (or creates) the file at `path` and prepares it to receive
+    /// records in the given `format`. Existing contents are preserved and
+    /// appended to rather than truncated, since `path` defaults to a fixed
+    /// name and re-running this tool must not wipe out credentials for
+    /// accounts created by a previous run.
+    pub fn new(path: &Path, format: OutputFormat) -> io::Result<Self> {
+        let is_new = path.metadata().map(|metadata| metadata.len() == 0).unwrap_or(true);
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(match format {
+            OutputFormat::Jsonl => Self::Jsonl(file),
+            // Only emit the header row for a brand-new (or empty) file, so
+            // appending to an existing one doesn't interleave extra headers
+            // with the data rows.
+            OutputFormat::Csv => Self::Csv(csv::WriterBuilder::new().has_headers(is_new).from_writer(file)),
+        })
+    }
+
+    /// Appends `record` and flushes immediately, so the file always reflects
+    /// every account created so far.
+    pub fn write(&mut self, record: &AccountRecord) -> io::Result<()> {
+        match self {
+            Self::Jsonl(file) => {
+                serde_json::to_writer(&mut *file, record)?;
+                writeln!(file)?;
+                Ok(())
+            }
+            Self::Csv(writer) => {
+                writer.serialize(record).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+                writer.flush()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn temp_path(extension: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("free-duolingo-plus-test-{}.{extension}", Uuid::new_v4()))
+    }
+
+    fn sample_record() -> AccountRecord {
+        AccountRecord { email: "a@b.com".to_string(), password: "hunter22222222".to_string(), id: 42, jwt: "abc.def.ghi".to_string() }
+    }
+
+    #[test]
+    fn jsonl_round_trip() {
+        let path = temp_path("jsonl");
+        let mut writer = OutputWriter::new(&path, OutputFormat::Jsonl).unwrap();
+
+        writer.write(&sample_record()).unwrap();
+        writer.write(&sample_record()).unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 2);
+
+        let parsed: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(parsed["email"], "a@b.com");
+        assert_eq!(parsed["id"], 42);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let path = temp_path("csv");
+        let mut writer = OutputWriter::new(&path, OutputFormat::Csv).unwrap();
+
+        writer.write(&sample_record()).unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let mut lines = contents.lines();
+        assert_eq!(lines.next(), Some("email,password,id,jwt"));
+        assert_eq!(lines.next(), Some("a@b.com,hunter22222222,42,abc.def.ghi"));
+
+        fs::remove_file(&path).unwrap();
+    }
+}